@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("{0}")]
@@ -6,8 +8,37 @@ pub enum Error {
     #[error("SSH forwarding network tunnel exit with non-zero exit code {0}")]
     TunnelExitNonZero(String),
 
-    // Used to bubble up SSH tunnel errors without logging any further errors
-    // this allows the last `ssh: ` log to be reported as the main error to the user
-    #[error("{0}")]
-    SSH(String)
+    #[error("invalid ssh endpoint: {0}")]
+    SshEndpoint(String),
+
+    #[error("ssh authentication failed: {0}")]
+    SshAuth(String),
+
+    #[error("failed to connect to ssh server: {0}")]
+    SshConnect(String),
+
+    #[error("{source}\n\nrecent ssh log:\n{}", .log.join("\n"))]
+    SshTunnelFailed {
+        #[source]
+        source: Box<Error>,
+        log: Vec<String>,
+    },
+
+    #[error("websocket tunnel error: {0}")]
+    Wss(String),
+
+    #[error("socks5 error: {0}")]
+    Socks5(String),
+
+    #[error("ssh server rejected remote forward request: {0}")]
+    SshForwardRejected(String),
+
+    #[error("ssh session appears to have died: {0}")]
+    SshSessionDead(String),
+
+    #[error("timed out after {0:?} waiting for local port {1} to accept connections")]
+    ReadinessTimeout(Duration, u16),
+
+    #[error("invalid tunnel configuration: {0}")]
+    InvalidConfig(String),
 }
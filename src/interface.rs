@@ -1,5 +1,7 @@
-use super::networktunnel::NetworkTunnel;
-use super::sshforwarding::{SshForwarding, SshForwardingConfig};
+use super::sshforwarding::SshForwardingConfig;
+use super::sshreverse::SshReverseConfig;
+use super::sshsocks::SshSocksConfig;
+use super::wsstunnel::WssTunnelConfig;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -8,4 +10,7 @@ use serde::{Deserialize, Serialize};
 #[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub enum NetworkTunnelConfig {
     SshForwarding(SshForwardingConfig),
+    SshSocks(SshSocksConfig),
+    SshReverse(SshReverseConfig),
+    Wss(WssTunnelConfig),
 }
@@ -0,0 +1,10 @@
+pub mod errors;
+pub mod interface;
+pub mod logbuffer;
+pub mod socks5;
+pub mod ssh;
+pub mod sshforwarding;
+pub mod sshreverse;
+pub mod sshsocks;
+pub mod tunnel;
+pub mod wsstunnel;
@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+
+/// A fixed-capacity ring buffer of recent diagnostic log lines.
+///
+/// Tunnels push every line of interest (connection attempts, auth failures,
+/// reconnect backoff, etc.) here as it happens. Errors from the SSH connect,
+/// authentication, remote-forward request and keepalive steps carry the
+/// buffered lines (see `ssh::wrap_failure`), so the surfaced message contains
+/// the actual diagnostic context. Other errors, such as a local listener
+/// failing, and all errors of the wss tunnel are returned without them.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    capacity: usize,
+    lines: VecDeque<String>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Append a line, evicting the oldest line if the buffer is at capacity.
+    pub fn push_line(&mut self, line: impl Into<String>) {
+        if self.lines.len() >= self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.into());
+    }
+
+    /// A snapshot of the buffered lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_line_accumulates_under_capacity() {
+        let mut buffer = LogBuffer::new(3);
+        buffer.push_line("one");
+        buffer.push_line("two");
+
+        assert_eq!(buffer.lines(), vec!["one".to_string(), "two".to_string()]);
+    }
+
+    #[test]
+    fn push_line_evicts_oldest_at_capacity() {
+        let mut buffer = LogBuffer::new(2);
+        buffer.push_line("one");
+        buffer.push_line("two");
+        buffer.push_line("three");
+
+        assert_eq!(buffer.lines(), vec!["two".to_string(), "three".to_string()]);
+    }
+
+    #[test]
+    fn zero_capacity_buffer_evicts_every_push() {
+        let mut buffer = LogBuffer::new(0);
+        buffer.push_line("one");
+        buffer.push_line("two");
+
+        assert_eq!(buffer.lines(), vec!["two".to_string()]);
+    }
+}
@@ -1,9 +1,16 @@
 use clap::Parser;
 use network_tunnel::errors::Error;
 use flow_cli_common::{init_logging, LogArgs};
-use futures::future::{self, TryFutureExt};
-use network_tunnel::{sshforwarding::{SshForwarding, SshForwardingConfig}, tunnel::NetworkTunnel};
+use network_tunnel::{
+    sshforwarding::{SshForwarding, SshForwardingConfig},
+    sshreverse::{SshReverse, SshReverseConfig},
+    sshsocks::{SshSocksConfig, SshSocksForwarding},
+    tunnel::NetworkTunnel,
+    wsstunnel::{WssTunnel, WssTunnelConfig},
+};
 use std::io;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
 
 #[derive(clap::Subcommand, Clone, Debug)]
 pub enum Command {
@@ -30,7 +37,170 @@ pub enum Command {
         /// This should match the port that's used in your connector configuration.
         #[clap(long)]
         local_port: u16,
-    }
+
+        /// Send a lightweight keepalive at this interval, in seconds, so we notice
+        /// a dead session promptly.
+        #[clap(long, default_value = "30")]
+        keepalive_interval_secs: u64,
+
+        /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+        /// Unset retries indefinitely.
+        #[clap(long)]
+        max_retries: Option<u32>,
+
+        /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+        #[clap(long, default_value = "500")]
+        retry_base_delay_ms: u64,
+
+        /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+        #[clap(long, default_value = "30000")]
+        retry_max_delay_ms: u64,
+
+        /// How long, in seconds, a reconnected session must stay up before the backoff
+        /// delay resets back down to `retry_base_delay_ms`.
+        #[clap(long, default_value = "60")]
+        healthy_threshold_secs: u64,
+    },
+
+    SshSocks {
+        /// Endpoint of the remote SSH server that supports tunneling, in the form of ssh://user@hostname[:port]
+        #[clap(long)]
+        ssh_endpoint: String,
+
+        #[clap(long)]
+        /// Path to private key file to connect to the remote SSH server. The file must have
+        /// permissions recommended by SSH (http://linuxcommand.org/lc3_man_pages/ssh1.html).
+        /// Recommended permissions: 600.
+        private_key: String,
+
+        /// The local port that will serve as a SOCKS5 proxy entrypoint into the SSH server.
+        /// This should match the port that's used in your connector configuration.
+        #[clap(long)]
+        local_port: u16,
+
+        /// Send a lightweight keepalive at this interval, in seconds, so we notice
+        /// a dead session promptly.
+        #[clap(long, default_value = "30")]
+        keepalive_interval_secs: u64,
+
+        /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+        /// Unset retries indefinitely.
+        #[clap(long)]
+        max_retries: Option<u32>,
+
+        /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+        #[clap(long, default_value = "500")]
+        retry_base_delay_ms: u64,
+
+        /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+        #[clap(long, default_value = "30000")]
+        retry_max_delay_ms: u64,
+
+        /// How long, in seconds, a reconnected session must stay up before the backoff
+        /// delay resets back down to `retry_base_delay_ms`.
+        #[clap(long, default_value = "60")]
+        healthy_threshold_secs: u64,
+    },
+
+    SshReverse {
+        /// Endpoint of the remote SSH server that supports tunneling, in the form of ssh://user@hostname[:port]
+        #[clap(long)]
+        ssh_endpoint: String,
+
+        #[clap(long)]
+        /// Path to private key file to connect to the remote SSH server. The file must have
+        /// permissions recommended by SSH (http://linuxcommand.org/lc3_man_pages/ssh1.html).
+        /// Recommended permissions: 600.
+        private_key: String,
+
+        /// The port to request the SSH server bind on its side.
+        #[clap(long)]
+        remote_bind_port: u16,
+
+        /// The local hostname that the remote bind port is forwarded to.
+        #[clap(long)]
+        local_host: String,
+
+        /// The local port that the remote bind port is forwarded to.
+        #[clap(long)]
+        local_port: u16,
+
+        /// Request the server bind the forwarded port on all of its interfaces
+        /// (0.0.0.0) rather than loopback only.
+        #[clap(long)]
+        gateway_ports: bool,
+
+        /// Send a lightweight keepalive at this interval, in seconds, so the
+        /// remote bind survives idle periods.
+        #[clap(long, default_value = "30")]
+        keepalive_interval_secs: u64,
+
+        /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+        /// Unset retries indefinitely.
+        #[clap(long)]
+        max_retries: Option<u32>,
+
+        /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+        #[clap(long, default_value = "500")]
+        retry_base_delay_ms: u64,
+
+        /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+        #[clap(long, default_value = "30000")]
+        retry_max_delay_ms: u64,
+
+        /// How long, in seconds, a reconnected session must stay up before the backoff
+        /// delay resets back down to `retry_base_delay_ms`.
+        #[clap(long, default_value = "60")]
+        healthy_threshold_secs: u64,
+    },
+
+    Wss {
+        /// URL of the WSS gateway, e.g. wss://gateway.example.com/tunnel
+        #[clap(long)]
+        gateway_url: String,
+
+        /// The hostname of the remote destination (e.g. the database server).
+        #[clap(long)]
+        forward_host: String,
+
+        /// The port of the remote destination (e.g. the database server).
+        #[clap(long)]
+        forward_port: u16,
+
+        /// The local port which will be connected to the remote host/port over the WSS tunnel.
+        #[clap(long)]
+        local_port: u16,
+
+        /// Optional bearer token sent as an Authorization header during the WebSocket upgrade.
+        #[clap(long)]
+        auth_token: Option<String>,
+
+        /// Whether to verify the gateway's TLS certificate.
+        #[clap(long, default_value = "true")]
+        verify_tls: bool,
+
+        /// Number of idle, already-upgraded WebSocket connections to keep ready in a pool.
+        #[clap(long, default_value = "4")]
+        pool_size: usize,
+
+        /// Maximum number of times to restart the tunnel after the local accept loop fails.
+        /// Unset retries indefinitely.
+        #[clap(long)]
+        max_retries: Option<u32>,
+
+        /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+        #[clap(long, default_value = "500")]
+        retry_base_delay_ms: u64,
+
+        /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+        #[clap(long, default_value = "30000")]
+        retry_max_delay_ms: u64,
+
+        /// How long, in seconds, the accept loop must stay up before the backoff
+        /// delay resets back down to `retry_base_delay_ms`.
+        #[clap(long, default_value = "60")]
+        healthy_threshold_secs: u64,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -40,6 +210,11 @@ pub struct Args {
     #[clap(subcommand)]
     command: Command,
 
+    /// How long, in seconds, to wait for the tunnel's local port(s) to start accepting
+    /// connections before giving up and reporting an error.
+    #[clap(long, default_value = "10")]
+    readiness_timeout_secs: u64,
+
     #[clap(flatten)]
     log_args: LogArgs,
 }
@@ -48,46 +223,90 @@ pub struct Args {
 async fn main() -> io::Result<()> {
     let Args {
         command,
+        readiness_timeout_secs,
         log_args,
     } = Args::parse();
 
     init_logging(&log_args);
 
-    if let Err(err) = run(command).await.as_ref() {
+    if let Err(err) = run(command, Duration::from_secs(readiness_timeout_secs)).await.as_ref() {
         tracing::error!(error = ?err, "network tunnel failed.");
         std::process::exit(1);
     }
     Ok(())
 }
 
-async fn run_and_cleanup(tunnel: &mut Box<dyn NetworkTunnel>) -> Result<(), Error> {
-    let tunnel_block = {
-        let prep = tunnel.prepare().await;
+/// Poll each of `ports` until it accepts a local TCP connection, or return a
+/// `ReadinessTimeout` error once `timeout` has elapsed for any one of them.
+/// Used in place of scraping log output to determine when a tunnel is truly
+/// ready to serve client requests, which works uniformly across tunnel types.
+async fn poll_until_ready(ports: &[u16], timeout: Duration) -> Result<(), Error> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    for &port in ports {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+                break;
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::ReadinessTimeout(timeout, port));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_and_cleanup(tunnel: &mut Box<dyn NetworkTunnel>, readiness_timeout: Duration) -> Result<(), Error> {
+    let tunnel_block = async {
+        tunnel.prepare().await?;
+
+        // `start_serve` is what actually binds/accepts on the tunnel's local port(s), so
+        // we race it against polling those ports: if it fails outright before the tunnel
+        // becomes ready, surface that error instead of waiting out the full timeout.
+        let ports = tunnel.local_ports();
+        let serve = tunnel.start_serve();
+        tokio::pin!(serve);
+
+        tokio::select! {
+            result = &mut serve => return result,
+            result = poll_until_ready(&ports, readiness_timeout) => result?,
+        }
 
-        // Write "READY" to stdio to unblock Go logic.
-        // The current workflow assumes that
-        //   1. After tunnel.prepare() is called, the network tunnel is able to accept requests from clients without sending errors back to clients.
-        //   2. The network tunnel is able to process client requests immediately after `tunnel.start_serve` is called.
-        // If either of the assumptions is invalid for any new tunnel type, the READY-logic need to be moved to a separate task, which
-        //    sends out the "READY" signal after making sure the network tunnel is started and working properly.
+        // Write "READY" to stdio to unblock Go logic, now that we've confirmed the
+        // tunnel's local port(s) are actually accepting connections, rather than just
+        // assuming readiness as soon as `prepare` returns.
         println!("READY");
 
-        future::ready(prep).and_then(|()| {
-            tunnel.start_serve()
-        }).await
+        serve.await
     };
 
+    let result = tunnel_block.await;
+
     // We must make sure we cleanup the child process. This is specially important
     // as processes that are not `wait`ed on can end up as zombies in some operating
     // systems (see https://doc.rust-lang.org/std/process/struct.Child.html#warning)
     tunnel.cleanup().await?;
 
-    tunnel_block
+    result
 }
 
-async fn run(cmd: Command) -> Result<(), Error> {
+async fn run(cmd: Command, readiness_timeout: Duration) -> Result<(), Error> {
     match cmd {
-        Command::SSH { ssh_endpoint, private_key, forward_host, forward_port, local_port } => {
+        Command::SSH {
+            ssh_endpoint,
+            private_key,
+            forward_host,
+            forward_port,
+            local_port,
+            keepalive_interval_secs,
+            max_retries,
+            retry_base_delay_ms,
+            retry_max_delay_ms,
+            healthy_threshold_secs,
+        } => {
             let mut tunnel: Box<dyn NetworkTunnel> = Box::new(SshForwarding::new(
                 SshForwardingConfig {
                     ssh_endpoint,
@@ -95,10 +314,106 @@ async fn run(cmd: Command) -> Result<(), Error> {
                     forward_host,
                     forward_port,
                     local_port,
+                    forwards: Vec::new(),
+                    keepalive_interval_secs,
+                    max_retries,
+                    retry_base_delay_ms,
+                    retry_max_delay_ms,
+                    healthy_threshold_secs,
+                }
+            ));
+
+            run_and_cleanup(&mut tunnel, readiness_timeout).await
+        }
+
+        Command::SshSocks {
+            ssh_endpoint,
+            private_key,
+            local_port,
+            keepalive_interval_secs,
+            max_retries,
+            retry_base_delay_ms,
+            retry_max_delay_ms,
+            healthy_threshold_secs,
+        } => {
+            let mut tunnel: Box<dyn NetworkTunnel> = Box::new(SshSocksForwarding::new(
+                SshSocksConfig {
+                    ssh_endpoint,
+                    private_key,
+                    local_port,
+                    keepalive_interval_secs,
+                    max_retries,
+                    retry_base_delay_ms,
+                    retry_max_delay_ms,
+                    healthy_threshold_secs,
+                }
+            ));
+
+            run_and_cleanup(&mut tunnel, readiness_timeout).await
+        }
+
+        Command::SshReverse {
+            ssh_endpoint,
+            private_key,
+            remote_bind_port,
+            local_host,
+            local_port,
+            gateway_ports,
+            keepalive_interval_secs,
+            max_retries,
+            retry_base_delay_ms,
+            retry_max_delay_ms,
+            healthy_threshold_secs,
+        } => {
+            let mut tunnel: Box<dyn NetworkTunnel> = Box::new(SshReverse::new(
+                SshReverseConfig {
+                    ssh_endpoint,
+                    private_key,
+                    remote_bind_port,
+                    local_host,
+                    local_port,
+                    gateway_ports,
+                    keepalive_interval_secs,
+                    max_retries,
+                    retry_base_delay_ms,
+                    retry_max_delay_ms,
+                    healthy_threshold_secs,
+                }
+            ));
+
+            run_and_cleanup(&mut tunnel, readiness_timeout).await
+        }
+
+        Command::Wss {
+            gateway_url,
+            forward_host,
+            forward_port,
+            local_port,
+            auth_token,
+            verify_tls,
+            pool_size,
+            max_retries,
+            retry_base_delay_ms,
+            retry_max_delay_ms,
+            healthy_threshold_secs,
+        } => {
+            let mut tunnel: Box<dyn NetworkTunnel> = Box::new(WssTunnel::new(
+                WssTunnelConfig {
+                    gateway_url,
+                    forward_host,
+                    forward_port,
+                    local_port,
+                    auth_token,
+                    verify_tls,
+                    pool_size,
+                    max_retries,
+                    retry_base_delay_ms,
+                    retry_max_delay_ms,
+                    healthy_threshold_secs,
                 }
             ));
 
-            run_and_cleanup(&mut tunnel).await
+            run_and_cleanup(&mut tunnel, readiness_timeout).await
         }
     }
 }
@@ -144,6 +459,10 @@ mod test {
             Ok(())
         }
 
+        fn local_ports(&self) -> Vec<u16> {
+            Vec::new()
+        }
+
         fn as_any(&self) -> &dyn Any {
             self
         }
@@ -158,7 +477,7 @@ mod test {
             error_in_serve: false,
         });
 
-        let result = run_and_cleanup(&mut tunnel).await;
+        let result = run_and_cleanup(&mut tunnel, std::time::Duration::from_secs(1)).await;
         assert!(result.is_err());
 
         let test_tunnel = tunnel.as_any().downcast_ref::<TestTunnel>().unwrap();
@@ -173,7 +492,7 @@ mod test {
             error_in_serve: true,
         });
 
-        let result = run_and_cleanup(&mut tunnel).await;
+        let result = run_and_cleanup(&mut tunnel, std::time::Duration::from_secs(1)).await;
         assert!(result.is_err());
 
         let test_tunnel = tunnel.as_any().downcast_ref::<TestTunnel>().unwrap();
@@ -188,7 +507,7 @@ mod test {
             error_in_serve: false,
         });
 
-        let result = run_and_cleanup(&mut tunnel).await;
+        let result = run_and_cleanup(&mut tunnel, std::time::Duration::from_secs(1)).await;
         assert!(result.is_ok());
 
         let test_tunnel = tunnel.as_any().downcast_ref::<TestTunnel>().unwrap();
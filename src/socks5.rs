@@ -0,0 +1,233 @@
+//! A minimal SOCKS5 server handshake (RFC 1928), just enough to learn the
+//! destination a client wants to reach and hand the connection off to an
+//! SSH `direct-tcpip` channel. Used by [`crate::sshsocks::SshSocksForwarding`]
+//! to implement dynamic forwarding (the equivalent of `ssh -D`).
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::errors::Error;
+use crate::ssh::{self, TunnelClient};
+
+use russh::client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+
+/// Perform the SOCKS5 greeting/request handshake on `conn`, open a
+/// `direct-tcpip` channel to the requested destination over `session`, and
+/// pump bytes between the two until either side closes.
+pub async fn serve(mut conn: TcpStream, session: &client::Handle<TunnelClient>) -> Result<(), Error> {
+    negotiate_auth(&mut conn).await?;
+    let (host, port) = read_connect_request(&mut conn).await?;
+
+    let channel = match session.channel_open_direct_tcpip(&host, port as u32, "127.0.0.1", 0).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            write_reply(&mut conn, REPLY_GENERAL_FAILURE).await?;
+            return Err(Error::Socks5(format!(
+                "ssh: direct-tcpip channel to {}:{} refused: {}",
+                host, port, e
+            )));
+        }
+    };
+
+    write_reply(&mut conn, REPLY_SUCCEEDED).await?;
+    ssh::pump_connection(conn, channel).await;
+
+    Ok(())
+}
+
+async fn negotiate_auth(conn: &mut TcpStream) -> Result<(), Error> {
+    let mut header = [0u8; 2];
+    conn.read_exact(&mut header).await?;
+    let [version, nmethods] = header;
+    if version != SOCKS_VERSION {
+        return Err(Error::Socks5(format!("unsupported socks version {}", version)));
+    }
+
+    let mut methods = vec![0u8; nmethods as usize];
+    conn.read_exact(&mut methods).await?;
+
+    // We only support unauthenticated access; the tunnel itself is already
+    // gated by SSH authentication.
+    conn.write_all(&[SOCKS_VERSION, AUTH_NONE]).await?;
+    Ok(())
+}
+
+async fn read_connect_request(conn: &mut TcpStream) -> Result<(String, u16), Error> {
+    let mut header = [0u8; 4];
+    conn.read_exact(&mut header).await?;
+    let [version, cmd, _reserved, atyp] = header;
+    if version != SOCKS_VERSION {
+        return Err(Error::Socks5(format!("unsupported socks version {}", version)));
+    }
+    if cmd != CMD_CONNECT {
+        write_reply(conn, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(Error::Socks5(format!("unsupported socks command {}", cmd)));
+    }
+
+    let host = match atyp {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            conn.read_exact(&mut addr).await?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            conn.read_exact(&mut addr).await?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            conn.read_exact(&mut len).await?;
+            let mut domain = vec![0u8; len[0] as usize];
+            conn.read_exact(&mut domain).await?;
+            String::from_utf8(domain)
+                .map_err(|e| Error::Socks5(format!("invalid socks domain name: {}", e)))?
+        }
+        _ => {
+            return Err(Error::Socks5(format!("unsupported socks address type {}", atyp)));
+        }
+    };
+
+    let mut port = [0u8; 2];
+    conn.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+
+    Ok((host, port))
+}
+
+async fn write_reply(conn: &mut TcpStream, reply: u8) -> Result<(), Error> {
+    // BND.ADDR/BND.PORT are unused by clients once the channel is opened, so
+    // we report the unspecified address (0.0.0.0:0) as most minimal SOCKS5
+    // servers do.
+    conn.write_all(&[SOCKS_VERSION, reply, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    /// A connected pair of loopback sockets, standing in for a SOCKS5 client
+    /// and the server-side connection `negotiate_auth`/`read_connect_request`
+    /// are handed in `serve`.
+    async fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn negotiate_auth_accepts_no_auth_handshake() {
+        let (mut client, mut server) = socket_pair().await;
+        client.write_all(&[SOCKS_VERSION, 1, AUTH_NONE]).await.unwrap();
+
+        negotiate_auth(&mut server).await.unwrap();
+
+        let mut response = [0u8; 2];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, [SOCKS_VERSION, AUTH_NONE]);
+    }
+
+    #[tokio::test]
+    async fn negotiate_auth_rejects_unsupported_version() {
+        let (mut client, mut server) = socket_pair().await;
+        client.write_all(&[0x04, 1, AUTH_NONE]).await.unwrap();
+
+        let err = negotiate_auth(&mut server).await.unwrap_err();
+        assert!(matches!(err, Error::Socks5(_)));
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_parses_ipv4() {
+        let (mut client, mut server) = socket_pair().await;
+        client
+            .write_all(&[SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_IPV4, 10, 0, 0, 1, 0x1F, 0x90])
+            .await
+            .unwrap();
+
+        let (host, port) = read_connect_request(&mut server).await.unwrap();
+        assert_eq!(host, "10.0.0.1");
+        assert_eq!(port, 8080);
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_parses_ipv6() {
+        let (mut client, mut server) = socket_pair().await;
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_IPV6];
+        request.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        request.extend_from_slice(&443u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let (host, port) = read_connect_request(&mut server).await.unwrap();
+        assert_eq!(host, Ipv6Addr::LOCALHOST.to_string());
+        assert_eq!(port, 443);
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_parses_domain() {
+        let (mut client, mut server) = socket_pair().await;
+        let domain = b"example.com";
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_DOMAIN, domain.len() as u8];
+        request.extend_from_slice(domain);
+        request.extend_from_slice(&22u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let (host, port) = read_connect_request(&mut server).await.unwrap();
+        assert_eq!(host, "example.com");
+        assert_eq!(port, 22);
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_rejects_invalid_utf8_domain() {
+        let (mut client, mut server) = socket_pair().await;
+        let domain = [0xFF, 0xFE];
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_DOMAIN, domain.len() as u8];
+        request.extend_from_slice(&domain);
+        request.extend_from_slice(&1234u16.to_be_bytes());
+        client.write_all(&request).await.unwrap();
+
+        let err = read_connect_request(&mut server).await.unwrap_err();
+        assert!(matches!(err, Error::Socks5(_)));
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_rejects_unsupported_command() {
+        let (mut client, mut server) = socket_pair().await;
+        const CMD_BIND: u8 = 0x02;
+        client
+            .write_all(&[SOCKS_VERSION, CMD_BIND, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+
+        let err = read_connect_request(&mut server).await.unwrap_err();
+        assert!(matches!(err, Error::Socks5(_)));
+    }
+
+    #[tokio::test]
+    async fn read_connect_request_rejects_unsupported_address_type() {
+        let (mut client, mut server) = socket_pair().await;
+        client
+            .write_all(&[SOCKS_VERSION, CMD_CONNECT, 0x00, 0x7F])
+            .await
+            .unwrap();
+
+        let err = read_connect_request(&mut server).await.unwrap_err();
+        assert!(matches!(err, Error::Socks5(_)));
+    }
+}
@@ -0,0 +1,506 @@
+//! Shared pieces for building an SSH tunnel on top of the native `russh`
+//! client: endpoint parsing, host-key handling, and the connect+authenticate
+//! sequence used by every `NetworkTunnel` backed by a single SSH session
+//! (local forwarding, SOCKS5, reverse forwarding, ...).
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::errors::Error;
+use crate::logbuffer::LogBuffer;
+use crate::tunnel::NetworkTunnel;
+
+use async_trait::async_trait;
+use russh::client;
+use russh_keys::key;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// The parsed pieces of a `ssh://user@hostname[:port]` endpoint.
+pub struct SshEndpoint {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+}
+
+pub fn parse_ssh_endpoint(endpoint: &str) -> Result<SshEndpoint, Error> {
+    let without_scheme = endpoint
+        .strip_prefix("ssh://")
+        .ok_or_else(|| Error::SshEndpoint(format!("endpoint {} is missing ssh:// scheme", endpoint)))?;
+
+    let (user, host_port) = without_scheme
+        .split_once('@')
+        .ok_or_else(|| Error::SshEndpoint(format!("endpoint {} is missing a user", endpoint)))?;
+
+    let (host, port) = match host_port.split_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .map_err(|_| Error::SshEndpoint(format!("invalid port in endpoint {}", endpoint)))?,
+        ),
+        None => (host_port, 22),
+    };
+
+    if user.is_empty() || host.is_empty() {
+        return Err(Error::SshEndpoint(format!(
+            "endpoint {} must be of the form ssh://user@hostname[:port]",
+            endpoint
+        )));
+    }
+
+    Ok(SshEndpoint {
+        user: user.to_string(),
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Number of recent diagnostic lines retained for the life of a tunnel.
+pub const LOG_BUFFER_CAPACITY: usize = 50;
+
+pub fn default_retry_base_delay_ms() -> u64 {
+    500
+}
+
+pub fn default_retry_max_delay_ms() -> u64 {
+    30_000
+}
+
+pub fn default_healthy_threshold_secs() -> u64 {
+    60
+}
+
+pub fn default_keepalive_interval_secs() -> u64 {
+    30
+}
+
+/// A `russh` client handler that trusts whatever host key the server presents.
+/// We intentionally do not pin host keys today: the remote endpoint is supplied
+/// by the connector configuration and reached over a network the operator
+/// already trusts to run the tunnel at all.
+pub struct TunnelClient;
+
+#[async_trait]
+impl client::Handler for TunnelClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        self,
+        _server_public_key: &key::PublicKey,
+    ) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+}
+
+/// Append a line to `log`, for use by tunnel implementations that hold their
+/// log buffer behind a `Mutex` so it can be shared with concurrently-running
+/// accept loops.
+pub async fn push_log(log: &Mutex<LogBuffer>, line: impl Into<String>) {
+    log.lock().await.push_line(line);
+}
+
+/// Wrap `err` with the diagnostic lines collected in `log` so far.
+pub async fn wrap_failure(log: &Mutex<LogBuffer>, err: Error) -> Error {
+    Error::SshTunnelFailed {
+        source: Box::new(err),
+        log: log.lock().await.lines(),
+    }
+}
+
+/// Connect to `ssh_endpoint` and authenticate with `private_key`, logging
+/// progress and failures to `log`. Returns the authenticated session handle.
+pub async fn connect(
+    ssh_endpoint: &str,
+    private_key: &str,
+    log: &Mutex<LogBuffer>,
+) -> Result<client::Handle<TunnelClient>, Error> {
+    connect_with_handler(ssh_endpoint, private_key, TunnelClient, log).await
+}
+
+/// Like [`connect`], but with a caller-supplied `client::Handler`. Tunnel
+/// types that need to react to server-initiated events (e.g. reverse
+/// forwarding's `forwarded-tcpip` channels) provide their own handler.
+pub async fn connect_with_handler<H: client::Handler>(
+    ssh_endpoint: &str,
+    private_key: &str,
+    handler: H,
+    log: &Mutex<LogBuffer>,
+) -> Result<client::Handle<H>, Error> {
+    let endpoint = match parse_ssh_endpoint(ssh_endpoint) {
+        Ok(endpoint) => endpoint,
+        Err(e) => return Err(wrap_failure(log, e).await),
+    };
+
+    let key_pair = match russh_keys::decode_secret_key(private_key, None) {
+        Ok(key_pair) => key_pair,
+        Err(e) => {
+            let msg = format!("failed to decode private key: {}", e);
+            push_log(log, format!("ssh: {}", msg)).await;
+            return Err(wrap_failure(log, Error::SshAuth(msg)).await);
+        }
+    };
+
+    push_log(log, format!("connecting to ssh server {}:{}", endpoint.host, endpoint.port)).await;
+    tracing::debug!("connecting to ssh server {}:{}", endpoint.host, endpoint.port);
+    let config = Arc::new(client::Config::default());
+    let mut session = match client::connect(config, (endpoint.host.as_str(), endpoint.port), handler).await {
+        Ok(session) => session,
+        Err(e) => {
+            let msg = format!("{}", e);
+            push_log(log, format!("ssh: connect failed: {}", msg)).await;
+            return Err(wrap_failure(log, Error::SshConnect(msg)).await);
+        }
+    };
+
+    let authenticated = match session.authenticate_publickey(&endpoint.user, Arc::new(key_pair)).await {
+        Ok(authenticated) => authenticated,
+        Err(e) => {
+            let msg = format!("{}", e);
+            push_log(log, format!("ssh: authentication error: {}", msg)).await;
+            return Err(wrap_failure(log, Error::SshAuth(msg)).await);
+        }
+    };
+    if !authenticated {
+        let msg = format!("server rejected public key for user {}", endpoint.user);
+        push_log(log, format!("ssh: {}", msg)).await;
+        return Err(wrap_failure(log, Error::SshAuth(msg)).await);
+    }
+
+    push_log(log, "ssh session authenticated & ready for serving requests").await;
+    tracing::debug!("ssh session authenticated & ready for serving requests");
+    Ok(session)
+}
+
+/// Copy bytes bidirectionally between an accepted local TCP connection and a
+/// `direct-tcpip` channel opened on an SSH session.
+pub async fn pump_connection(local: TcpStream, mut channel: russh::Channel<client::Msg>) {
+    let (mut local_read, mut local_write) = local.into_split();
+    let mut channel_stream = channel.make_writer_ext(None);
+
+    let to_remote = async {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let n = match local_read.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if channel_stream.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let from_remote = async {
+        while let Some(msg) = channel.wait().await {
+            if let russh::ChannelMsg::Data { data } = msg {
+                if local_write.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        }
+    };
+
+    tokio::join!(to_remote, from_remote);
+}
+
+/// Periodically probe `session` with a throwaway channel until it fails,
+/// which we treat as a signal that the session has gone away. Connection
+/// failures on individual forwarded/proxied channels are isolated per
+/// connection attempt and never surface here, so tunnel types whose
+/// `serve_once` only watches a local listener need this as their actual
+/// liveness signal to drive reconnect.
+pub async fn keepalive_probe<H: client::Handler>(
+    session: &client::Handle<H>,
+    log: &Mutex<LogBuffer>,
+    interval_secs: u64,
+) -> Result<(), Error> {
+    let interval = Duration::from_secs(interval_secs.max(1));
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        // A throwaway session channel doubles as both a keepalive (it resets
+        // the server's idle timer) and a liveness probe: if the session has
+        // gone away, opening it will fail.
+        match session.channel_open_session().await {
+            Ok(channel) => {
+                let _ = channel.close().await;
+            }
+            Err(e) => {
+                let msg = format!("keepalive failed: {}", e);
+                push_log(log, format!("ssh: {}", msg)).await;
+                return Err(wrap_failure(log, Error::SshSessionDead(msg)).await);
+            }
+        }
+    }
+}
+
+/// The reconnect/backoff knobs shared by every tunnel type built around
+/// [`run_with_reconnect`].
+pub struct RetryConfig {
+    pub max_retries: Option<u32>,
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    pub healthy_threshold_secs: u64,
+}
+
+/// A `NetworkTunnel` whose `start_serve` is just `run_with_reconnect(self)`.
+/// `serve_once` does the tunnel-specific work (accepting/forwarding connections,
+/// or probing liveness) and returns once the underlying session has gone away;
+/// `prepare`/`cleanup` (from `NetworkTunnel`) re-establish and tear down that
+/// session around each reconnect attempt.
+#[async_trait]
+pub trait Reconnectable: NetworkTunnel {
+    async fn serve_once(&mut self) -> Result<(), Error>;
+    fn retry_config(&self) -> RetryConfig;
+    fn log(&self) -> &Mutex<LogBuffer>;
+    /// Used only in log messages, e.g. "ssh", "ssh socks5", "ssh reverse", "wss".
+    fn kind(&self) -> &'static str;
+}
+
+/// Run `tunnel.serve_once()` in a loop, reconnecting (`cleanup` + `prepare`)
+/// with exponential backoff between attempts -- reset back to the base delay
+/// once a reconnect has stayed up longer than `healthy_threshold_secs` -- up to
+/// an optional `max_retries` cap.
+pub async fn run_with_reconnect(tunnel: &mut impl Reconnectable) -> Result<(), Error> {
+    let retry = tunnel.retry_config();
+    let base_delay = Duration::from_millis(retry.retry_base_delay_ms);
+    let max_delay = Duration::from_millis(retry.retry_max_delay_ms);
+    let healthy_threshold = Duration::from_secs(retry.healthy_threshold_secs);
+
+    let mut delay = base_delay;
+    let mut attempt: u32 = 0;
+    // A failed `prepare` during a reconnect counts as another failed attempt
+    // rather than ending the loop; `serve_once` is skipped until one succeeds.
+    let mut prepare_err: Option<Error> = None;
+
+    loop {
+        let connected_at = Instant::now();
+        let err = match prepare_err.take() {
+            Some(err) => err,
+            None => match tunnel.serve_once().await {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            },
+        };
+
+        if connected_at.elapsed() >= healthy_threshold {
+            delay = base_delay;
+            attempt = 0;
+        }
+        attempt += 1;
+
+        if let Some(max_retries) = retry.max_retries {
+            if attempt > max_retries {
+                tracing::error!(error = ?err, attempt, "{} tunnel exhausted reconnect attempts", tunnel.kind());
+                return Err(err);
+            }
+        }
+
+        push_log(
+            tunnel.log(),
+            format!("{} tunnel dropped (attempt {}), reconnecting in {:?}", tunnel.kind(), attempt, delay),
+        )
+        .await;
+        tracing::warn!(
+            error = ?err,
+            attempt,
+            delay_ms = delay.as_millis() as u64,
+            "{} tunnel dropped, reconnecting",
+            tunnel.kind(),
+        );
+
+        tunnel.cleanup().await?;
+        tokio::time::sleep(delay).await;
+        prepare_err = tunnel.prepare().await.err();
+
+        delay = std::cmp::min(delay * 2, max_delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_endpoint_with_explicit_port() {
+        let endpoint = parse_ssh_endpoint("ssh://alice@example.com:2222").unwrap();
+        assert_eq!(endpoint.user, "alice");
+        assert_eq!(endpoint.host, "example.com");
+        assert_eq!(endpoint.port, 2222);
+    }
+
+    #[test]
+    fn parses_endpoint_defaulting_to_port_22() {
+        let endpoint = parse_ssh_endpoint("ssh://alice@example.com").unwrap();
+        assert_eq!(endpoint.port, 22);
+    }
+
+    #[test]
+    fn rejects_endpoint_missing_scheme() {
+        let err = parse_ssh_endpoint("alice@example.com").unwrap_err();
+        assert!(matches!(err, Error::SshEndpoint(_)));
+    }
+
+    #[test]
+    fn rejects_endpoint_missing_user() {
+        let err = parse_ssh_endpoint("ssh://example.com").unwrap_err();
+        assert!(matches!(err, Error::SshEndpoint(_)));
+    }
+
+    #[test]
+    fn rejects_endpoint_with_invalid_port() {
+        let err = parse_ssh_endpoint("ssh://alice@example.com:notaport").unwrap_err();
+        assert!(matches!(err, Error::SshEndpoint(_)));
+    }
+
+    /// A `Reconnectable` whose `serve_once` fails its first `fail_times` calls
+    /// (recording when each surrounding `prepare`/`cleanup` ran) before
+    /// succeeding, for exercising `run_with_reconnect`'s backoff/reconnect
+    /// state machine without a real SSH session.
+    struct FakeTunnel {
+        fail_times: u32,
+        attempts: u32,
+        prepare_times: Vec<Instant>,
+        cleanup_times: Vec<Instant>,
+        max_retries: Option<u32>,
+        retry_base_delay_ms: u64,
+        retry_max_delay_ms: u64,
+        healthy_threshold_secs: u64,
+        log: Mutex<LogBuffer>,
+    }
+
+    #[async_trait]
+    impl NetworkTunnel for FakeTunnel {
+        async fn prepare(&mut self) -> Result<(), Error> {
+            self.prepare_times.push(Instant::now());
+            Ok(())
+        }
+
+        async fn start_serve(&mut self) -> Result<(), Error> {
+            run_with_reconnect(self).await
+        }
+
+        async fn cleanup(&mut self) -> Result<(), Error> {
+            self.cleanup_times.push(Instant::now());
+            Ok(())
+        }
+
+        fn local_ports(&self) -> Vec<u16> {
+            Vec::new()
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+    }
+
+    #[async_trait]
+    impl Reconnectable for FakeTunnel {
+        async fn serve_once(&mut self) -> Result<(), Error> {
+            self.attempts += 1;
+            if self.attempts <= self.fail_times {
+                Err(Error::InvalidConfig(format!("fake failure {}", self.attempts)))
+            } else {
+                Ok(())
+            }
+        }
+
+        fn retry_config(&self) -> RetryConfig {
+            RetryConfig {
+                max_retries: self.max_retries,
+                retry_base_delay_ms: self.retry_base_delay_ms,
+                retry_max_delay_ms: self.retry_max_delay_ms,
+                healthy_threshold_secs: self.healthy_threshold_secs,
+            }
+        }
+
+        fn log(&self) -> &Mutex<LogBuffer> {
+            &self.log
+        }
+
+        fn kind(&self) -> &'static str {
+            "fake"
+        }
+    }
+
+    impl FakeTunnel {
+        fn new(fail_times: u32) -> Self {
+            Self {
+                fail_times,
+                attempts: 0,
+                prepare_times: Vec::new(),
+                cleanup_times: Vec::new(),
+                max_retries: None,
+                retry_base_delay_ms: 5,
+                retry_max_delay_ms: 10_000,
+                healthy_threshold_secs: 9999,
+                log: Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY)),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn run_with_reconnect_returns_err_once_max_retries_exhausted() {
+        let mut tunnel = FakeTunnel::new(u32::MAX);
+        tunnel.max_retries = Some(2);
+        tunnel.retry_base_delay_ms = 1;
+        tunnel.retry_max_delay_ms = 10;
+
+        let err = run_with_reconnect(&mut tunnel).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+
+        // 2 reconnect attempts, plus the 3rd failure that exceeds the cap.
+        assert_eq!(tunnel.attempts, 3);
+        // The attempt that exceeds the cap returns immediately, without
+        // running cleanup/prepare for another reconnect.
+        assert_eq!(tunnel.cleanup_times.len(), 2);
+        assert_eq!(tunnel.prepare_times.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn run_with_reconnect_doubles_delay_on_repeated_failures() {
+        let mut tunnel = FakeTunnel::new(3);
+        tunnel.retry_base_delay_ms = 20;
+
+        let result = run_with_reconnect(&mut tunnel).await;
+        assert!(result.is_ok());
+        assert_eq!(tunnel.cleanup_times.len(), 3);
+        assert_eq!(tunnel.prepare_times.len(), 3);
+
+        let gap = |i: usize| tunnel.prepare_times[i] - tunnel.cleanup_times[i];
+        let (gap0, gap1, gap2) = (gap(0), gap(1), gap(2));
+
+        // Each backoff should be roughly double the previous one; use a
+        // looser-than-2x bound to tolerate scheduler jitter.
+        assert!(gap1 > gap0 + gap0 / 2, "expected {:?} to be roughly double {:?}", gap1, gap0);
+        assert!(gap2 > gap1 + gap1 / 2, "expected {:?} to be roughly double {:?}", gap2, gap1);
+    }
+
+    #[tokio::test]
+    async fn run_with_reconnect_resets_delay_after_healthy_period() {
+        let mut tunnel = FakeTunnel::new(4);
+        tunnel.retry_base_delay_ms = 10;
+        // A `serve_once` that stays up even momentarily counts as "healthy"
+        // with a zero threshold, so the backoff should reset every time
+        // instead of doubling.
+        tunnel.healthy_threshold_secs = 0;
+
+        let result = run_with_reconnect(&mut tunnel).await;
+        assert!(result.is_ok());
+        assert_eq!(tunnel.cleanup_times.len(), 4);
+
+        let gaps: Vec<Duration> = (0..4).map(|i| tunnel.prepare_times[i] - tunnel.cleanup_times[i]).collect();
+        let max_gap = *gaps.iter().max().unwrap();
+
+        // If the healthy-threshold reset didn't kick in, doubling unchecked
+        // would put the 4th gap at ~8x the base delay; with the reset in
+        // effect every gap should stay close to `retry_base_delay_ms`.
+        assert!(
+            max_gap < Duration::from_millis(10 * 5),
+            "delay should reset each time instead of growing: {:?}",
+            gaps
+        );
+    }
+}
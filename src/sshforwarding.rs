@@ -1,19 +1,40 @@
 use std::any::Any;
-use std::io::ErrorKind;
-use std::process::Stdio;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
 
 use crate::errors::Error;
+use crate::logbuffer::LogBuffer;
+use crate::ssh::{
+    self, default_healthy_threshold_secs, default_keepalive_interval_secs, default_retry_base_delay_ms,
+    default_retry_max_delay_ms, Reconnectable, RetryConfig, TunnelClient, LOG_BUFFER_CAPACITY,
+};
 use crate::tunnel::NetworkTunnel;
 
 use async_trait::async_trait;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Child;
-use tokio::process::Command;
+use futures::future;
+use russh::client;
+use russh::Disconnect;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
 
 use serde::{Deserialize, Serialize};
 
 pub const ENDPOINT_ADDRESS_KEY: &str = "address";
 
+/// A single local-port-to-remote-destination forwarding, multiplexed as one
+/// of potentially several channels over a shared SSH session.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PortForward {
+    /// The local port which will be connected to the remote host/port over an SSH tunnel.
+    pub local_port: u16,
+    /// The hostname of the remote destination (e.g. the database server).
+    pub forward_host: String,
+    /// The port of the remote destination (e.g. the database server).
+    pub forward_port: u16,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct SshForwardingConfig {
@@ -22,27 +43,132 @@ pub struct SshForwardingConfig {
     /// Private key to connect to the remote SSH server.
     pub private_key: String,
     /// The hostname of the remote destination (e.g. the database server).
+    ///
+    /// Deprecated in favor of `forwards`, kept for backward compatibility with
+    /// existing single-forward configs. Ignored if `forwards` is non-empty.
     #[serde(default)]
     pub forward_host: String,
     /// The port of the remote destination (e.g. the database server).
+    ///
+    /// Deprecated in favor of `forwards`, see above.
     #[serde(default)]
     pub forward_port: u16,
     /// The local port which will be connected to the remote host/port over an SSH tunnel.
     /// This should match the port that's used in your basic connector configuration.
+    ///
+    /// Deprecated in favor of `forwards`, see above.
     #[serde(default)]
     pub local_port: u16,
+    /// One or more local-port-to-remote-destination forwardings, all multiplexed
+    /// over a single SSH connection. If empty, falls back to the single forward
+    /// described by `local_port`/`forward_host`/`forward_port`.
+    #[serde(default)]
+    pub forwards: Vec<PortForward>,
+    /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+    /// `None` (the default) retries indefinitely.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+    /// How long, in seconds, a reconnected session must stay up before the backoff
+    /// delay resets back down to `retry_base_delay_ms`.
+    #[serde(default = "default_healthy_threshold_secs")]
+    pub healthy_threshold_secs: u64,
+    /// Send a lightweight keepalive at this interval, in seconds, so we notice
+    /// a dead session promptly: a refused/failed `direct-tcpip` channel open
+    /// only affects its own connection attempt and otherwise never surfaces
+    /// as a reason to reconnect.
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+}
+
+impl SshForwardingConfig {
+    /// The effective set of forwardings to multiplex over the SSH session,
+    /// folding the legacy single-forward fields into a one-element vector
+    /// when `forwards` isn't set. Errors if neither `forwards` nor the legacy
+    /// fields were configured, rather than silently falling back to an
+    /// all-default forward that binds a random ephemeral local port.
+    pub fn port_forwards(&self) -> Result<Vec<PortForward>, Error> {
+        if !self.forwards.is_empty() {
+            return Ok(self.forwards.clone());
+        }
+
+        if self.local_port == 0 && self.forward_host.is_empty() && self.forward_port == 0 {
+            return Err(Error::InvalidConfig(
+                "must set either `forwards` or the legacy `localPort`/`forwardHost`/`forwardPort` fields".to_string(),
+            ));
+        }
+
+        Ok(vec![PortForward {
+            local_port: self.local_port,
+            forward_host: self.forward_host.clone(),
+            forward_port: self.forward_port,
+        }])
+    }
 }
 
 pub struct SshForwarding {
     config: SshForwardingConfig,
-    process: Option<Child>,
+    session: Option<Arc<client::Handle<TunnelClient>>>,
+    listeners: Option<Vec<(PortForward, TcpListener)>>,
+    log: Arc<Mutex<LogBuffer>>,
 }
 
 impl SshForwarding {
     pub fn new(config: SshForwardingConfig) -> Self {
         Self {
             config,
-            process: None,
+            session: None,
+            listeners: None,
+            log: Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY))),
+        }
+    }
+
+    /// Accept connections for a single forward and pump each one through a
+    /// `direct-tcpip` channel. A refused/failed channel open only affects that
+    /// one connection attempt (logged and dropped) rather than the accept loop:
+    /// the loop only stops if the listener itself fails.
+    async fn serve_forward(
+        &self,
+        session: Arc<client::Handle<TunnelClient>>,
+        forward: &PortForward,
+        listener: &TcpListener,
+    ) -> Result<(), Error> {
+        loop {
+            let (local_conn, peer_addr) = listener.accept().await?;
+            ssh::push_log(
+                &self.log,
+                format!("accepted connection from {} on local port {}", peer_addr, forward.local_port),
+            )
+            .await;
+            tracing::debug!(local_port = forward.local_port, "accepted connection from {}", peer_addr);
+
+            let session = Arc::clone(&session);
+            let forward = forward.clone();
+            let log = Arc::clone(&self.log);
+            tokio::spawn(async move {
+                let channel = match session
+                    .channel_open_direct_tcpip(&forward.forward_host, forward.forward_port as u32, "127.0.0.1", 0)
+                    .await
+                {
+                    Ok(channel) => channel,
+                    Err(e) => {
+                        let msg = format!(
+                            "ssh: direct-tcpip channel to {}:{} refused: {}",
+                            forward.forward_host, forward.forward_port, e
+                        );
+                        ssh::push_log(&log, msg).await;
+                        tracing::warn!(local_port = forward.local_port, error = ?e, "direct-tcpip channel refused");
+                        return;
+                    }
+                };
+
+                ssh::pump_connection(local_conn, channel).await;
+            });
         }
     }
 }
@@ -50,118 +176,167 @@ impl SshForwarding {
 #[async_trait]
 impl NetworkTunnel for SshForwarding {
     async fn prepare(&mut self) -> Result<(), Error> {
-        let local_port = self.config.local_port;
-        let ssh_endpoint = &self.config.ssh_endpoint;
-        let forward_host = &self.config.forward_host;
-        let forward_port = self.config.forward_port;
-
-        tracing::info!(
-            "ssh forwarding local port {} to remote host {}:{}",
-            local_port,
-            forward_host,
-            forward_port
-        );
-
-        tracing::debug!("spawning ssh tunnel");
-        let mut child = Command::new("ssh")
-            .args(vec![
-                // Disable psuedo-terminal allocation
-                "-T".to_string(),
-                // Be verbose so we can pick up signals about status of the tunnel
-                "-v".to_string(),
-                // This is necessary unless we also ask for the public key from users
-                "-o".to_string(),
-                "StrictHostKeyChecking no".to_string(),
-                // Ask the client to time out after 5 seconds
-                "-o".to_string(),
-                "ConnectTimeout=5".to_string(),
-                // Send period keepalive messages to the server to keep the
-                // connection from being closed due to inactivity.
-                "-o".to_string(),
-                "ServerAliveInterval=30".to_string(),
-                // Pass the private key
-                "-i".to_string(),
-                self.config.private_key.clone(),
-                // Do not execute a remote command. Just forward the ports.
-                "-N".to_string(),
-                // Port forwarding stanza
-                "-L".to_string(),
-                format!("{local_port}:{forward_host}:{forward_port}"),
-                ssh_endpoint.to_string(),
-            ])
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        // Read stderr of SSH until we find a signal message that
-        // the ports are open and we are ready to serve requests
-        let stderr = child.stderr.take().unwrap();
-        let mut lines = BufReader::new(stderr).lines();
-        self.process = Some(child);
-
-        tracing::debug!("listening on ssh tunnel stderr");
-        while let Some(line) = lines.next_line().await? {
-            // OpenSSH will enter interactive session after tunnelling has been
-            // successful
-            if line.contains("Entering interactive session.") {
-                tracing::debug!("ssh tunnel is listening & ready for serving requests");
-                return Ok(());
-            }
-
-            // Otherwise apply a little bit of intelligence to translate OpenSSH
-            // log messages to appropriate connector_proxy log levels.
-            if line.starts_with("debug1:") {
-                tracing::debug!("ssh: {}", &line);
-            } else if line.starts_with("Warning: Permanently added") {
-                tracing::debug!("ssh: {}", &line);
-            } else if line.contains("Permission denied") {
-                tracing::error!("ssh: {}", &line);
-            } else if line.contains("Network is unreachable") {
-                tracing::error!("ssh: {}", &line);
-            } else if line.contains("Connection timed out") {
-                tracing::error!("ssh: {}", &line);
-            } else {
-                tracing::info!("ssh: {}", &line);
-            }
+        for forward in self.config.port_forwards()? {
+            tracing::info!(
+                "ssh forwarding local port {} to remote host {}:{}",
+                forward.local_port,
+                forward.forward_host,
+                forward.forward_port,
+            );
         }
 
-        // This function's job was just to launch the SSH tunnel and wait until
-        // it's ready to serve traffic. If stderr closes unexpectedly we treat
-        // this as a probably-erroneous form of 'success', and rely on the later
-        // `start_serve` exit code checking to report a failure.
-        tracing::warn!("unexpected end of output from ssh tunnel");
+        let session = ssh::connect(&self.config.ssh_endpoint, &self.config.private_key, &self.log).await?;
+        self.session = Some(Arc::new(session));
+
         Ok(())
     }
 
     async fn start_serve(&mut self) -> Result<(), Error> {
-        tracing::debug!("awaiting ssh tunnel process");
-        let exit_status = self.process.as_mut().unwrap().wait().await?;
-        if !exit_status.success() {
-            tracing::error!(
-                exit_code = ?exit_status.code(),
-                message = "network tunnel ssh exit with non-zero code."
-            );
-
-            return Err(Error::TunnelExitNonZero(format!("{:#?}", exit_status)));
+        if self.listeners.is_none() {
+            let mut bound = Vec::new();
+            for forward in self.config.port_forwards()? {
+                let listener = TcpListener::bind(("127.0.0.1", forward.local_port)).await?;
+                tracing::debug!("listening on local port {}", forward.local_port);
+                bound.push((forward, listener));
+            }
+            self.listeners = Some(bound);
         }
 
-        Ok(())
+        ssh::run_with_reconnect(self).await
     }
 
     async fn cleanup(&mut self) -> Result<(), Error> {
-        if let Some(process) = self.process.as_mut() {
-            match process.kill().await {
-                // InvalidInput means the process has already exited, in which case
-                // we do not need to cleanup the process
-                Err(e) if e.kind() == ErrorKind::InvalidInput => Ok(()),
-                a => a,
-            }?;
+        if let Some(session) = self.session.take() {
+            let _ = session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await;
         }
 
         Ok(())
     }
 
+    fn local_ports(&self) -> Vec<u16> {
+        self.config
+            .port_forwards()
+            .map(|forwards| forwards.iter().map(|f| f.local_port).collect())
+            .unwrap_or_default()
+    }
+
     // This is only used for testing
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+#[async_trait]
+impl Reconnectable for SshForwarding {
+    /// Run the accept loop for every configured forward concurrently alongside
+    /// a keepalive liveness probe, returning as soon as any one of them
+    /// reports the SSH session has gone away. The accept loops alone aren't
+    /// enough: a refused/failed `direct-tcpip` channel open only affects its
+    /// own connection attempt (logged and dropped in `serve_forward`), so the
+    /// keepalive is what actually detects a dead session and drives reconnect.
+    async fn serve_once(&mut self) -> Result<(), Error> {
+        let session = Arc::clone(
+            self.session
+                .as_ref()
+                .expect("prepare must be called before start_serve"),
+        );
+        let listeners = self.listeners.as_ref().expect("listeners bound in start_serve");
+
+        type BoxedServe<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+        let mut tasks: Vec<BoxedServe> = listeners
+            .iter()
+            .map(|(forward, listener)| -> BoxedServe {
+                Box::pin(self.serve_forward(Arc::clone(&session), forward, listener))
+            })
+            .collect();
+        tasks.push(Box::pin(ssh::keepalive_probe(
+            &session,
+            &self.log,
+            self.config.keepalive_interval_secs,
+        )));
+
+        let (result, _, _) = future::select_all(tasks).await;
+        result
+    }
+
+    fn retry_config(&self) -> RetryConfig {
+        RetryConfig {
+            max_retries: self.config.max_retries,
+            retry_base_delay_ms: self.config.retry_base_delay_ms,
+            retry_max_delay_ms: self.config.retry_max_delay_ms,
+            healthy_threshold_secs: self.config.healthy_threshold_secs,
+        }
+    }
+
+    fn log(&self) -> &Mutex<LogBuffer> {
+        &self.log
+    }
+
+    fn kind(&self) -> &'static str {
+        "ssh"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_config() -> SshForwardingConfig {
+        SshForwardingConfig {
+            ssh_endpoint: "ssh://user@example.com".to_string(),
+            private_key: String::new(),
+            forward_host: String::new(),
+            forward_port: 0,
+            local_port: 0,
+            forwards: Vec::new(),
+            max_retries: None,
+            retry_base_delay_ms: default_retry_base_delay_ms(),
+            retry_max_delay_ms: default_retry_max_delay_ms(),
+            healthy_threshold_secs: default_healthy_threshold_secs(),
+            keepalive_interval_secs: default_keepalive_interval_secs(),
+        }
+    }
+
+    #[test]
+    fn prefers_forwards_over_legacy_fields() {
+        let mut config = base_config();
+        config.local_port = 1111;
+        config.forward_host = "legacy.example.com".to_string();
+        config.forward_port = 2222;
+        config.forwards = vec![PortForward {
+            local_port: 3333,
+            forward_host: "multi.example.com".to_string(),
+            forward_port: 4444,
+        }];
+
+        let forwards = config.port_forwards().unwrap();
+        assert_eq!(forwards, config.forwards);
+    }
+
+    #[test]
+    fn falls_back_to_legacy_fields_when_forwards_empty() {
+        let mut config = base_config();
+        config.local_port = 1111;
+        config.forward_host = "legacy.example.com".to_string();
+        config.forward_port = 2222;
+
+        let forwards = config.port_forwards().unwrap();
+        assert_eq!(
+            forwards,
+            vec![PortForward {
+                local_port: 1111,
+                forward_host: "legacy.example.com".to_string(),
+                forward_port: 2222,
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_config_with_neither_forwards_nor_legacy_fields() {
+        let err = base_config().port_forwards().unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)));
+    }
+}
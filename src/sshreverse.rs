@@ -0,0 +1,229 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::errors::Error;
+use crate::logbuffer::LogBuffer;
+use crate::ssh::{
+    self, default_healthy_threshold_secs, default_keepalive_interval_secs, default_retry_base_delay_ms,
+    default_retry_max_delay_ms, Reconnectable, RetryConfig, LOG_BUFFER_CAPACITY,
+};
+use crate::tunnel::NetworkTunnel;
+
+use async_trait::async_trait;
+use russh::client;
+use russh::Disconnect;
+use russh_keys::key;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Config for a reverse (remote -> local) port-forwarding tunnel, equivalent
+/// to `ssh -R remote_bind_port:local_host:local_port`. This lets a service
+/// reachable from the SSH server reach back to something running locally,
+/// e.g. a connector publishing a webhook endpoint through a bastion.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SshReverseConfig {
+    /// Endpoint of the remote SSH server that supports tunneling, in the form of ssh://user@hostname[:port]
+    pub ssh_endpoint: String,
+    /// Private key to connect to the remote SSH server.
+    pub private_key: String,
+    /// The port to request the SSH server bind on its side.
+    pub remote_bind_port: u16,
+    /// The local hostname that the remote bind port is forwarded to.
+    pub local_host: String,
+    /// The local port that the remote bind port is forwarded to.
+    pub local_port: u16,
+    /// Request the server bind the forwarded port on all of its interfaces
+    /// (0.0.0.0) rather than loopback only. Only takes effect if the server's
+    /// `GatewayPorts` setting is `yes` or `clientspecified`.
+    #[serde(default)]
+    pub gateway_ports: bool,
+    /// Send a lightweight keepalive at this interval, in seconds, so the
+    /// remote bind survives idle periods (the client-side equivalent of
+    /// sshd's `ClientAliveInterval`) and so we notice a dead session promptly.
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+    /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+    /// `None` (the default) retries indefinitely.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+    /// How long, in seconds, a reconnected session must stay up before the backoff
+    /// delay resets back down to `retry_base_delay_ms`.
+    #[serde(default = "default_healthy_threshold_secs")]
+    pub healthy_threshold_secs: u64,
+}
+
+/// A `russh` client handler that accepts `forwarded-tcpip` channels opened by
+/// the server in response to our `tcpip_forward` request, and connects each
+/// one to `local_host:local_port`.
+struct ReverseTunnelClient {
+    local_host: String,
+    local_port: u16,
+    log: Arc<Mutex<LogBuffer>>,
+}
+
+#[async_trait]
+impl client::Handler for ReverseTunnelClient {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        self,
+        _server_public_key: &key::PublicKey,
+    ) -> Result<(Self, bool), Self::Error> {
+        Ok((self, true))
+    }
+
+    async fn server_channel_open_forwarded_tcpip(
+        self,
+        channel: russh::Channel<client::Msg>,
+        _connected_address: &str,
+        _connected_port: u32,
+        originator_address: &str,
+        originator_port: u32,
+        session: client::Session,
+    ) -> Result<(Self, client::Session), Self::Error> {
+        let local_host = self.local_host.clone();
+        let local_port = self.local_port;
+        let log = Arc::clone(&self.log);
+
+        ssh::push_log(
+            &log,
+            format!(
+                "remote peer {}:{} connected, forwarding to {}:{}",
+                originator_address, originator_port, local_host, local_port
+            ),
+        )
+        .await;
+
+        tokio::spawn(async move {
+            match TcpStream::connect((local_host.as_str(), local_port)).await {
+                Ok(local_conn) => ssh::pump_connection(local_conn, channel).await,
+                Err(e) => {
+                    ssh::push_log(
+                        &log,
+                        format!("reverse forward: failed to connect to {}:{}: {}", local_host, local_port, e),
+                    )
+                    .await;
+                }
+            }
+        });
+
+        Ok((self, session))
+    }
+}
+
+pub struct SshReverse {
+    config: SshReverseConfig,
+    session: Option<Arc<client::Handle<ReverseTunnelClient>>>,
+    log: Arc<Mutex<LogBuffer>>,
+}
+
+impl SshReverse {
+    pub fn new(config: SshReverseConfig) -> Self {
+        Self {
+            config,
+            session: None,
+            log: Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY))),
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkTunnel for SshReverse {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        tracing::info!(
+            "ssh reverse forwarding remote port {} to local {}:{}",
+            self.config.remote_bind_port,
+            self.config.local_host,
+            self.config.local_port,
+        );
+
+        let handler = ReverseTunnelClient {
+            local_host: self.config.local_host.clone(),
+            local_port: self.config.local_port,
+            log: Arc::clone(&self.log),
+        };
+
+        let mut session =
+            ssh::connect_with_handler(&self.config.ssh_endpoint, &self.config.private_key, handler, &self.log).await?;
+
+        let bind_address = if self.config.gateway_ports { "0.0.0.0" } else { "localhost" };
+        if let Err(e) = session.tcpip_forward(bind_address, self.config.remote_bind_port as u32).await {
+            let msg = format!(
+                "ssh: failed to request remote forward on {}:{}: {}",
+                bind_address, self.config.remote_bind_port, e
+            );
+            ssh::push_log(&self.log, msg.clone()).await;
+            return Err(ssh::wrap_failure(&self.log, Error::SshForwardRejected(msg)).await);
+        }
+
+        self.session = Some(Arc::new(session));
+
+        Ok(())
+    }
+
+    async fn start_serve(&mut self) -> Result<(), Error> {
+        ssh::run_with_reconnect(self).await
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Error> {
+        if let Some(session) = self.session.take() {
+            let _ = session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await;
+        }
+
+        Ok(())
+    }
+
+    fn local_ports(&self) -> Vec<u16> {
+        // Reverse forwarding connects out to local_host:local_port itself; it
+        // doesn't bind a local listener for clients to connect to.
+        Vec::new()
+    }
+
+    // This is only used for testing
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl Reconnectable for SshReverse {
+    /// Periodically probe the session with a lightweight keepalive until it
+    /// fails, which we treat as a signal that the remote bind has gone away.
+    async fn serve_once(&mut self) -> Result<(), Error> {
+        let session = Arc::clone(
+            self.session
+                .as_ref()
+                .expect("prepare must be called before start_serve"),
+        );
+
+        ssh::keepalive_probe(&session, &self.log, self.config.keepalive_interval_secs).await
+    }
+
+    fn retry_config(&self) -> RetryConfig {
+        RetryConfig {
+            max_retries: self.config.max_retries,
+            retry_base_delay_ms: self.config.retry_base_delay_ms,
+            retry_max_delay_ms: self.config.retry_max_delay_ms,
+            healthy_threshold_secs: self.config.healthy_threshold_secs,
+        }
+    }
+
+    fn log(&self) -> &Mutex<LogBuffer> {
+        &self.log
+    }
+
+    fn kind(&self) -> &'static str {
+        "ssh reverse"
+    }
+}
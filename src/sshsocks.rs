@@ -0,0 +1,173 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use crate::errors::Error;
+use crate::logbuffer::LogBuffer;
+use crate::ssh::{
+    self, default_healthy_threshold_secs, default_keepalive_interval_secs, default_retry_base_delay_ms,
+    default_retry_max_delay_ms, Reconnectable, RetryConfig, TunnelClient, LOG_BUFFER_CAPACITY,
+};
+use crate::tunnel::NetworkTunnel;
+
+use async_trait::async_trait;
+use russh::client;
+use russh::Disconnect;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Config for a dynamic SOCKS5 forwarding tunnel, equivalent to `ssh -D`.
+/// Unlike `SshForwardingConfig`, there's no fixed remote destination: the
+/// SOCKS5 client picks the destination per-connection, and we open a
+/// `direct-tcpip` channel to whatever it asks for.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SshSocksConfig {
+    /// Endpoint of the remote SSH server that supports tunneling, in the form of ssh://user@hostname[:port]
+    pub ssh_endpoint: String,
+    /// Private key to connect to the remote SSH server.
+    pub private_key: String,
+    /// The local port that will serve as a SOCKS5 proxy entrypoint into the SSH server.
+    pub local_port: u16,
+    /// Send a lightweight keepalive at this interval, in seconds, so we notice
+    /// a dead session promptly: a refused/failed `direct-tcpip` channel open
+    /// only affects its own connection attempt and otherwise never surfaces
+    /// as a reason to reconnect.
+    #[serde(default = "default_keepalive_interval_secs")]
+    pub keepalive_interval_secs: u64,
+    /// Maximum number of times to reconnect after the SSH session drops unexpectedly.
+    /// `None` (the default) retries indefinitely.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+    /// How long, in seconds, a reconnected session must stay up before the backoff
+    /// delay resets back down to `retry_base_delay_ms`.
+    #[serde(default = "default_healthy_threshold_secs")]
+    pub healthy_threshold_secs: u64,
+}
+
+pub struct SshSocksForwarding {
+    config: SshSocksConfig,
+    session: Option<Arc<client::Handle<TunnelClient>>>,
+    listener: Option<TcpListener>,
+    log: Arc<Mutex<LogBuffer>>,
+}
+
+impl SshSocksForwarding {
+    pub fn new(config: SshSocksConfig) -> Self {
+        Self {
+            config,
+            session: None,
+            listener: None,
+            log: Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY))),
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkTunnel for SshSocksForwarding {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        tracing::info!(
+            "ssh socks5 forwarding on local port {} via {}",
+            self.config.local_port,
+            self.config.ssh_endpoint,
+        );
+
+        let session = ssh::connect(&self.config.ssh_endpoint, &self.config.private_key, &self.log).await?;
+        self.session = Some(Arc::new(session));
+
+        Ok(())
+    }
+
+    async fn start_serve(&mut self) -> Result<(), Error> {
+        if self.listener.is_none() {
+            self.listener = Some(TcpListener::bind(("127.0.0.1", self.config.local_port)).await?);
+            tracing::debug!("listening on local port {}", self.config.local_port);
+        }
+
+        ssh::run_with_reconnect(self).await
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Error> {
+        if let Some(session) = self.session.take() {
+            let _ = session
+                .disconnect(Disconnect::ByApplication, "", "English")
+                .await;
+        }
+
+        Ok(())
+    }
+
+    fn local_ports(&self) -> Vec<u16> {
+        vec![self.config.local_port]
+    }
+
+    // This is only used for testing
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl Reconnectable for SshSocksForwarding {
+    /// Accept connections and speak just enough SOCKS5 to learn the requested
+    /// destination, then open a `direct-tcpip` channel to it and pump bytes
+    /// bidirectionally, alongside a keepalive liveness probe. The accept loop
+    /// alone isn't enough: a refused/failed `direct-tcpip` channel open only
+    /// affects its own connection attempt (logged and dropped by the
+    /// per-connection task below), so the
+    /// keepalive is what actually detects a dead session and drives reconnect.
+    async fn serve_once(&mut self) -> Result<(), Error> {
+        let listener = self.listener.as_ref().expect("listener bound in start_serve");
+        let session = Arc::clone(
+            self.session
+                .as_ref()
+                .expect("prepare must be called before start_serve"),
+        );
+
+        let accept_loop = async {
+            loop {
+                let (local_conn, peer_addr) = listener.accept().await?;
+                ssh::push_log(&self.log, format!("accepted socks5 connection from {}", peer_addr)).await;
+                tracing::debug!("accepted socks5 connection from {}", peer_addr);
+
+                let session = Arc::clone(&session);
+                let log = Arc::clone(&self.log);
+                tokio::spawn(async move {
+                    if let Err(e) = crate::socks5::serve(local_conn, &session).await {
+                        ssh::push_log(&log, format!("socks5: {}", e)).await;
+                        tracing::warn!(error = ?e, "socks5 connection failed");
+                    }
+                });
+            }
+        };
+
+        tokio::select! {
+            result = accept_loop => result,
+            result = ssh::keepalive_probe(&session, &self.log, self.config.keepalive_interval_secs) => result,
+        }
+    }
+
+    fn retry_config(&self) -> RetryConfig {
+        RetryConfig {
+            max_retries: self.config.max_retries,
+            retry_base_delay_ms: self.config.retry_base_delay_ms,
+            retry_max_delay_ms: self.config.retry_max_delay_ms,
+            healthy_threshold_secs: self.config.healthy_threshold_secs,
+        }
+    }
+
+    fn log(&self) -> &Mutex<LogBuffer> {
+        &self.log
+    }
+
+    fn kind(&self) -> &'static str {
+        "ssh socks5"
+    }
+}
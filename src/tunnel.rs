@@ -14,6 +14,12 @@ pub trait NetworkTunnel: Send + Sync {
     // is properly killed.
     async fn cleanup(&mut self) -> Result<(), Error>;
 
+    // The local port(s), if any, that this tunnel binds a listener on. Used to probe for
+    // actual readiness (a local connection is accepted) rather than assuming the tunnel
+    // is ready as soon as `prepare` returns. Tunnels with no local listener (e.g. reverse
+    // forwarding) return an empty vec.
+    fn local_ports(&self) -> Vec<u16>;
+
     // This is only used for testing purposes
     fn as_any(&self) -> &dyn Any;
 }
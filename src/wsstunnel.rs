@@ -0,0 +1,400 @@
+use std::any::Any;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::errors::Error;
+use crate::logbuffer::LogBuffer;
+use crate::ssh::{
+    self, default_healthy_threshold_secs, default_retry_base_delay_ms, default_retry_max_delay_ms, Reconnectable,
+    RetryConfig, LOG_BUFFER_CAPACITY,
+};
+use crate::tunnel::NetworkTunnel;
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::header::AUTHORIZATION;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{Connector, MaybeTlsStream, WebSocketStream};
+
+fn default_verify_tls() -> bool {
+    true
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+/// Config for tunneling a single TCP destination over a WebSocket-over-TLS
+/// (WSS) connection to a gateway, for networks where only outbound 443 is
+/// allowed and SSH can't connect directly.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WssTunnelConfig {
+    /// URL of the WSS gateway, e.g. `wss://gateway.example.com/tunnel`.
+    pub gateway_url: String,
+    /// The hostname of the remote destination (e.g. the database server), forwarded
+    /// to the gateway so it knows where to connect on our behalf.
+    pub forward_host: String,
+    /// The port of the remote destination (e.g. the database server).
+    pub forward_port: u16,
+    /// The local port which will be connected to the remote host/port over the WSS tunnel.
+    pub local_port: u16,
+    /// Optional bearer token sent as an `Authorization: Bearer <token>` header
+    /// during the WebSocket upgrade.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// Whether to verify the gateway's TLS certificate. Defaults to `true`;
+    /// only disable for testing against a gateway with a self-signed cert.
+    #[serde(default = "default_verify_tls")]
+    pub verify_tls: bool,
+    /// Number of idle, already-upgraded WebSocket connections to keep ready in
+    /// a pool, to avoid paying the TLS+WS handshake latency on every new
+    /// local connection.
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    /// Maximum number of times to restart the tunnel (pool and accept loop)
+    /// after the local accept loop fails. Failed gateway connects are retried
+    /// by the pool itself and do not count here.
+    /// `None` (the default) retries indefinitely.
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    /// Initial backoff delay, in milliseconds, before the first reconnect attempt.
+    #[serde(default = "default_retry_base_delay_ms")]
+    pub retry_base_delay_ms: u64,
+    /// Ceiling, in milliseconds, that the exponential reconnect backoff is capped at.
+    #[serde(default = "default_retry_max_delay_ms")]
+    pub retry_max_delay_ms: u64,
+    /// How long, in seconds, the accept loop must stay up before the backoff
+    /// delay resets back down to `retry_base_delay_ms`.
+    #[serde(default = "default_healthy_threshold_secs")]
+    pub healthy_threshold_secs: u64,
+}
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Open and upgrade a fresh WebSocket-over-TLS connection to the gateway.
+async fn open_ws_connection(config: &WssTunnelConfig) -> Result<WsStream, Error> {
+    let mut request = config
+        .gateway_url
+        .clone()
+        .into_client_request()
+        .map_err(|e| Error::Wss(format!("invalid gateway url {}: {}", config.gateway_url, e)))?;
+
+    request.headers_mut().insert(
+        "x-forward-host",
+        config
+            .forward_host
+            .parse()
+            .map_err(|e| Error::Wss(format!("invalid forward host {}: {}", config.forward_host, e)))?,
+    );
+    request.headers_mut().insert(
+        "x-forward-port",
+        config
+            .forward_port
+            .to_string()
+            .parse()
+            .map_err(|e| Error::Wss(format!("invalid forward port: {}", e)))?,
+    );
+    if let Some(token) = &config.auth_token {
+        let value = format!("Bearer {}", token);
+        request.headers_mut().insert(
+            AUTHORIZATION,
+            value
+                .parse()
+                .map_err(|e| Error::Wss(format!("invalid auth token: {}", e)))?,
+        );
+    }
+
+    let connector = if config.verify_tls {
+        None
+    } else {
+        Some(Connector::NativeTls(
+            native_tls::TlsConnector::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .map_err(|e| Error::Wss(format!("failed to build permissive tls connector: {}", e)))?,
+        ))
+    };
+
+    let (ws, _response) = tokio_tungstenite::connect_async_tls_with_config(request, None, false, connector)
+        .await
+        .map_err(|e| Error::Wss(format!("failed to open websocket to {}: {}", config.gateway_url, e)))?;
+
+    Ok(ws)
+}
+
+/// Copy bytes bidirectionally between an accepted local TCP connection and a
+/// WSS connection, framing each direction's bytes as binary WebSocket messages.
+async fn pump_ws_connection(local: TcpStream, ws: WsStream) {
+    let (mut local_read, mut local_write) = local.into_split();
+    let (mut ws_write, mut ws_read) = ws.split();
+
+    let to_remote = async {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let n = match local_read.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if ws_write.send(Message::Binary(buf[..n].to_vec())).await.is_err() {
+                break;
+            }
+        }
+        let _ = ws_write.close().await;
+    };
+
+    let from_remote = async {
+        while let Some(Ok(msg)) = ws_read.next().await {
+            if let Message::Binary(data) = msg {
+                if local_write.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        }
+    };
+
+    tokio::join!(to_remote, from_remote);
+}
+
+/// Maintains `pool_size` idle, upgraded connections ready to hand out to new
+/// local connections, replenishing them in the background by calling
+/// `connect` whenever the pool has room. Generic over the connection type `T`
+/// so the replenishment loop can be exercised in tests without a real
+/// WebSocket handshake; production code only ever instantiates this with
+/// `T = WsStream` and `connect = open_ws_connection`.
+struct ConnectionPool<T> {
+    idle: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> ConnectionPool<T> {
+    fn spawn<F, Fut>(pool_size: usize, log: Arc<Mutex<LogBuffer>>, connect: F) -> Self
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(pool_size.max(1));
+        tokio::spawn(async move {
+            loop {
+                match connect().await {
+                    Ok(item) => {
+                        if tx.send(item).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        log.lock().await.push_line(format!("wss: failed to pre-warm pool connection: {}", e));
+                        tokio::time::sleep(Duration::from_millis(500)).await;
+                    }
+                }
+            }
+        });
+
+        Self { idle: rx }
+    }
+
+    /// Take an idle connection if one is ready, without waiting for the pool
+    /// to replenish.
+    fn try_take(&mut self) -> Option<T> {
+        self.idle.try_recv().ok()
+    }
+}
+
+pub struct WssTunnel {
+    config: Arc<WssTunnelConfig>,
+    listener: Option<TcpListener>,
+    pool: Option<ConnectionPool<WsStream>>,
+    log: Arc<Mutex<LogBuffer>>,
+}
+
+impl WssTunnel {
+    pub fn new(config: WssTunnelConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            listener: None,
+            pool: None,
+            log: Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY))),
+        }
+    }
+}
+
+#[async_trait]
+impl NetworkTunnel for WssTunnel {
+    async fn prepare(&mut self) -> Result<(), Error> {
+        tracing::info!(
+            "wss forwarding local port {} to {}:{} via {}",
+            self.config.local_port,
+            self.config.forward_host,
+            self.config.forward_port,
+            self.config.gateway_url,
+        );
+
+        let config = Arc::clone(&self.config);
+        self.pool = Some(ConnectionPool::spawn(self.config.pool_size, Arc::clone(&self.log), move || {
+            let config = Arc::clone(&config);
+            async move { open_ws_connection(&config).await }
+        }));
+
+        Ok(())
+    }
+
+    async fn start_serve(&mut self) -> Result<(), Error> {
+        if self.listener.is_none() {
+            self.listener = Some(TcpListener::bind(("127.0.0.1", self.config.local_port)).await?);
+            tracing::debug!("listening on local port {}", self.config.local_port);
+        }
+
+        ssh::run_with_reconnect(self).await
+    }
+
+    async fn cleanup(&mut self) -> Result<(), Error> {
+        self.pool = None;
+        Ok(())
+    }
+
+    fn local_ports(&self) -> Vec<u16> {
+        vec![self.config.local_port]
+    }
+
+    // This is only used for testing
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl Reconnectable for WssTunnel {
+    /// Accept connections and hand each one a pooled (or freshly opened) WSS
+    /// connection to pump bytes through. A failed on-demand upgrade on a pool
+    /// miss only affects that one connection attempt (logged and dropped)
+    /// rather than the accept loop: the loop only stops if the listener itself
+    /// fails.
+    async fn serve_once(&mut self) -> Result<(), Error> {
+        let listener = self.listener.as_ref().expect("listener bound in start_serve");
+        let pool = self.pool.as_mut().expect("pool started in prepare");
+
+        loop {
+            let (local_conn, peer_addr) = listener.accept().await?;
+            self.log.lock().await.push_line(format!("accepted connection from {}", peer_addr));
+            tracing::debug!("accepted connection from {}", peer_addr);
+
+            let pooled = pool.try_take();
+            let config = Arc::clone(&self.config);
+            let log = Arc::clone(&self.log);
+            tokio::spawn(async move {
+                let ws = match pooled {
+                    Some(ws) => ws,
+                    None => {
+                        tracing::debug!("pool empty, opening websocket connection on the hot path");
+                        match open_ws_connection(&config).await {
+                            Ok(ws) => ws,
+                            Err(e) => {
+                                log.lock().await.push_line(format!("wss: failed to open on-demand connection: {}", e));
+                                tracing::warn!(error = ?e, "wss: failed to open on-demand connection");
+                                return;
+                            }
+                        }
+                    }
+                };
+
+                pump_ws_connection(local_conn, ws).await;
+            });
+        }
+    }
+
+    fn retry_config(&self) -> RetryConfig {
+        RetryConfig {
+            max_retries: self.config.max_retries,
+            retry_base_delay_ms: self.config.retry_base_delay_ms,
+            retry_max_delay_ms: self.config.retry_max_delay_ms,
+            healthy_threshold_secs: self.config.healthy_threshold_secs,
+        }
+    }
+
+    fn log(&self) -> &Mutex<LogBuffer> {
+        &self.log
+    }
+
+    fn kind(&self) -> &'static str {
+        "wss"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn pool_fills_up_to_capacity() {
+        let log = Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY)));
+        let next = Arc::new(AtomicUsize::new(0));
+
+        let mut pool = ConnectionPool::spawn(3, log, move || {
+            let next = Arc::clone(&next);
+            async move { Ok::<usize, Error>(next.fetch_add(1, Ordering::SeqCst)) }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for _ in 0..3 {
+            assert!(pool.try_take().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn pool_replenishes_after_an_item_is_taken() {
+        let log = Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY)));
+        let next = Arc::new(AtomicUsize::new(0));
+
+        let mut pool = ConnectionPool::spawn(1, log, move || {
+            let next = Arc::clone(&next);
+            async move { Ok::<usize, Error>(next.fetch_add(1, Ordering::SeqCst)) }
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(pool.try_take().is_some());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(pool.try_take().is_some());
+    }
+
+    #[tokio::test]
+    async fn pool_try_take_on_not_yet_filled_pool_returns_none() {
+        let log = Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY)));
+
+        let mut pool = ConnectionPool::spawn(1, log, || futures::future::pending::<Result<usize, Error>>());
+
+        assert!(pool.try_take().is_none());
+    }
+
+    #[tokio::test]
+    async fn pool_keeps_retrying_after_a_connect_error() {
+        let log = Arc::new(Mutex::new(LogBuffer::new(LOG_BUFFER_CAPACITY)));
+        let attempts = Arc::new(AtomicUsize::new(0));
+
+        let mut pool = ConnectionPool::spawn(1, Arc::clone(&log), move || {
+            let attempts = Arc::clone(&attempts);
+            async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err(Error::Wss("simulated connect failure".to_string()))
+                } else {
+                    Ok(42usize)
+                }
+            }
+        });
+
+        // The first attempt fails and the loop backs off for 500ms before
+        // retrying; give it enough time to recover and fill the pool.
+        tokio::time::sleep(Duration::from_millis(700)).await;
+
+        assert_eq!(pool.try_take(), Some(42));
+        assert!(log.lock().await.lines().iter().any(|line| line.contains("failed to pre-warm")));
+    }
+}